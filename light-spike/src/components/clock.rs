@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use crate::prelude::*;
+
+/// A source of the current time, abstracted so that trusting-period
+/// expiration and clock-drift checks can be exercised deterministically in
+/// tests via [`MockClock`], while the demuxer uses [`SystemClock`] in
+/// production.
+pub trait Clock: std::fmt::Debug {
+    fn now(&self) -> Time;
+}
+
+/// Reads the current time off the system clock.
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Time {
+        Time::now()
+    }
+}
+
+/// A clock whose `now()` is set explicitly, for deterministic testing of
+/// expiration and clock-drift paths.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Time,
+}
+
+impl MockClock {
+    pub fn new(now: Time) -> Self {
+        Self { now }
+    }
+
+    pub fn set(&mut self, now: Time) {
+        self.now = now;
+    }
+
+    pub fn advance(&mut self, by: Duration) {
+        self.now = self.now + by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Time {
+        self.now
+    }
+}
@@ -0,0 +1,242 @@
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use futures::{SinkExt, StreamExt};
+use tokio::runtime::Runtime;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use crate::prelude::*;
+
+#[derive(Debug)]
+pub enum IoInput {
+    FetchLightBlock(Height),
+}
+
+#[derive(Debug)]
+pub enum IoOutput {
+    FetchedLightBlock(LightBlock),
+}
+
+#[derive(Debug)]
+pub enum IoError {
+    Rpc(String),
+    Subscription(String),
+}
+
+/// A handler capable of servicing `IoInput` requests on behalf of an `Io`
+/// component. `RpcIo` services them with a blocking request/response round
+/// trip; `WebSocketIo` additionally keeps a persistent subscription open so
+/// new blocks can be pushed to the demuxer as they're produced.
+pub trait IoHandler: std::fmt::Debug {
+    fn handle(&mut self, input: IoInput) -> Result<IoOutput, IoError>;
+}
+
+#[derive(Debug)]
+pub struct Io {
+    handler: Box<dyn IoHandler>,
+}
+
+impl Io {
+    pub fn new(handler: impl IoHandler + 'static) -> Self {
+        Self {
+            handler: Box::new(handler),
+        }
+    }
+
+    pub fn process(&mut self, input: IoInput) -> Result<IoOutput, IoError> {
+        self.handler.handle(input)
+    }
+}
+
+/// Blocking RPC-backed `Io` handler: every `FetchLightBlock` request opens a
+/// fresh request/response round trip against `rpc_address`.
+#[derive(Debug)]
+pub struct RpcIo {
+    rpc_address: String,
+}
+
+impl RpcIo {
+    pub fn new(rpc_address: impl Into<String>) -> Self {
+        Self {
+            rpc_address: rpc_address.into(),
+        }
+    }
+}
+
+impl IoHandler for RpcIo {
+    fn handle(&mut self, input: IoInput) -> Result<IoOutput, IoError> {
+        match input {
+            IoInput::FetchLightBlock(height) => {
+                let light_block = fetch_light_block_via_rpc(&self.rpc_address, height)
+                    .map_err(IoError::Rpc)?;
+
+                Ok(IoOutput::FetchedLightBlock(light_block))
+            }
+        }
+    }
+}
+
+/// WebSocket-backed `Io` handler.
+///
+/// Opens a persistent connection to `rpc_address` and subscribes to the
+/// node's `NewBlock`/`NewBlockHeader` events, so the demuxer can react to
+/// pushed headers instead of polling a height at a time. Only the header's
+/// height and time are decoded; validator sets are not.
+/// Decoded light blocks are handed off over an unbounded channel; drain it
+/// with [`WebSocketIo::subscribed_blocks`] (see `Demuxer::run_subscription`).
+/// `FetchLightBlock` is still serviced with the same one-off HTTP `/commit`
+/// request that `RpcIo` makes, not over the WebSocket connection, so
+/// `WebSocketIo` is a drop-in replacement for `RpcIo`.
+#[derive(Debug)]
+pub struct WebSocketIo {
+    rpc_address: String,
+    runtime: Runtime,
+    subscribed_blocks_rx: Receiver<LightBlock>,
+}
+
+const NEW_BLOCK_SUBSCRIPTION_QUERY: &str = "tm.event='NewBlock'";
+const NEW_BLOCK_HEADER_SUBSCRIPTION_QUERY: &str = "tm.event='NewBlockHeader'";
+
+impl WebSocketIo {
+    pub fn connect(rpc_address: impl Into<String>) -> Result<Self, IoError> {
+        let rpc_address = rpc_address.into();
+        let runtime = Runtime::new().map_err(|e| IoError::Subscription(e.to_string()))?;
+        let (tx, rx) = unbounded();
+
+        runtime.spawn(run_subscription_loop(rpc_address.clone(), tx));
+
+        Ok(Self {
+            rpc_address,
+            runtime,
+            subscribed_blocks_rx: rx,
+        })
+    }
+
+    /// The channel that newly pushed light blocks are fed into as the
+    /// subscription receives `NewBlock`/`NewBlockHeader` events.
+    pub fn subscribed_blocks(&self) -> &Receiver<LightBlock> {
+        &self.subscribed_blocks_rx
+    }
+}
+
+impl IoHandler for WebSocketIo {
+    fn handle(&mut self, input: IoInput) -> Result<IoOutput, IoError> {
+        match input {
+            IoInput::FetchLightBlock(height) => {
+                let rpc_address = self.rpc_address.clone();
+                let light_block = self
+                    .runtime
+                    .block_on(async move { fetch_light_block_via_ws(&rpc_address, height).await })
+                    .map_err(IoError::Rpc)?;
+
+                Ok(IoOutput::FetchedLightBlock(light_block))
+            }
+        }
+    }
+}
+
+async fn run_subscription_loop(rpc_address: String, tx: Sender<LightBlock>) {
+    let url = format!("ws://{}/websocket", rpc_address);
+
+    let (mut ws_stream, _) = match connect_async(&url).await {
+        Ok(conn) => conn,
+        Err(_) => return,
+    };
+
+    // Tendermint's `subscribe` RPC takes a single query per call, so
+    // `NewBlock` and `NewBlockHeader` each need their own subscription over
+    // this same connection; both arrive on the same stream below.
+    let subscriptions = [
+        ("new_block", NEW_BLOCK_SUBSCRIPTION_QUERY),
+        ("new_block_header", NEW_BLOCK_HEADER_SUBSCRIPTION_QUERY),
+    ];
+
+    for (id, query) in subscriptions {
+        let subscribe_request = subscribe_request_payload(id, query);
+
+        if ws_stream
+            .send(Message::Text(subscribe_request))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+
+    while let Some(Ok(message)) = ws_stream.next().await {
+        if let Message::Text(text) = message {
+            if let Some(light_block) = decode_subscription_event(&text) {
+                // The subscription loop outlives the `WebSocketIo` it feeds;
+                // a send error just means the receiving end was dropped.
+                let _ = tx.send(light_block);
+            }
+        }
+    }
+}
+
+async fn fetch_light_block_via_ws(rpc_address: &str, height: Height) -> Result<LightBlock, String> {
+    fetch_light_block_via_rpc(rpc_address, height)
+}
+
+fn subscribe_request_payload(id: &str, query: &str) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","method":"subscribe","id":"{}","params":{{"query":"{}"}}}}"#,
+        id, query
+    )
+}
+
+/// Decodes a `NewBlock` or `NewBlockHeader` event frame into a `LightBlock`.
+/// The two events nest their header at different paths (`NewBlock` under
+/// `block.header`, `NewBlockHeader` directly under `header`); other frames
+/// (e.g. the JSON-RPC acknowledgement of a `subscribe` call itself) don't
+/// carry a header at either path and are skipped by returning `None`, rather
+/// than treated as an error.
+fn decode_subscription_event(event: &str) -> Option<LightBlock> {
+    let frame: serde_json::Value = serde_json::from_str(event).ok()?;
+    let value = &frame["result"]["data"]["value"];
+
+    let header = if value["header"].is_null() {
+        &value["block"]["header"]
+    } else {
+        &value["header"]
+    };
+
+    if header.is_null() {
+        return None;
+    }
+
+    light_block_from_header(header).ok()
+}
+
+fn fetch_light_block_via_rpc(rpc_address: &str, height: Height) -> Result<LightBlock, String> {
+    let url = format!("http://{}/commit?height={}", rpc_address, u64::from(height));
+
+    let response: serde_json::Value = ureq::get(&url)
+        .call()
+        .map_err(|e| e.to_string())?
+        .into_json()
+        .map_err(|e| e.to_string())?;
+
+    let header = &response["result"]["signed_header"]["header"];
+
+    light_block_from_header(header)
+}
+
+/// Builds a `LightBlock` out of the `header` object shared by both the
+/// `/commit` RPC response and `NewBlock` event frames.
+fn light_block_from_header(header: &serde_json::Value) -> Result<LightBlock, String> {
+    let height = header["height"]
+        .as_str()
+        .ok_or_else(|| "missing header.height".to_owned())?
+        .parse::<u64>()
+        .map_err(|e| e.to_string())?;
+
+    let time = header["time"]
+        .as_str()
+        .ok_or_else(|| "missing header.time".to_owned())?
+        .parse::<Time>()
+        .map_err(|_| "could not parse header.time".to_owned())?;
+
+    Ok(LightBlock {
+        height: Height::from(height),
+        time,
+    })
+}
@@ -0,0 +1,5 @@
+pub mod clock;
+pub mod demuxer;
+pub mod io;
+pub mod scheduler;
+pub mod verifier;
@@ -0,0 +1,46 @@
+use crate::prelude::*;
+
+#[derive(Debug)]
+pub enum VerifierError {
+    /// The signed commit doesn't carry enough of the trusted validators'
+    /// voting power to cross the configured trust threshold.
+    InsufficientValidatorOverlap,
+    InvalidHeader(String),
+}
+
+#[derive(Clone, Debug)]
+pub enum VerifierInput {
+    VerifyLightBlock {
+        light_block: LightBlock,
+        trusted_state: TrustedState,
+        options: VerificationOptions,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub enum VerifierOutput {
+    ValidLightBlock(LightBlock),
+}
+
+/// A handler capable of servicing `VerifierInput` requests on behalf of a
+/// `Verifier` component.
+pub trait VerifierHandler: std::fmt::Debug {
+    fn handle(&mut self, input: VerifierInput) -> Result<VerifierOutput, VerifierError>;
+}
+
+#[derive(Debug)]
+pub struct Verifier {
+    handler: Box<dyn VerifierHandler>,
+}
+
+impl Verifier {
+    pub fn new(handler: impl VerifierHandler + 'static) -> Self {
+        Self {
+            handler: Box::new(handler),
+        }
+    }
+
+    pub fn process(&mut self, input: VerifierInput) -> Result<VerifierOutput, VerifierError> {
+        self.handler.handle(input)
+    }
+}
@@ -1,7 +1,6 @@
-// TODO: Replace this in-memory store with a proper `sled` based implementation
-
 use std::{
     collections::BTreeMap,
+    fmt::Debug,
     marker::PhantomData,
     sync::{Arc, RwLock},
 };
@@ -14,6 +13,72 @@ pub struct Trusted;
 #[derive(Debug)]
 pub struct Untrusted;
 
+/// Identifies the sled tree a given marker type is persisted under, so that
+/// the trusted and untrusted stores can share a single database file.
+pub trait StoreTree {
+    const TREE_NAME: &'static str;
+}
+
+impl StoreTree for Trusted {
+    const TREE_NAME: &'static str = "trusted";
+}
+
+impl StoreTree for Untrusted {
+    const TREE_NAME: &'static str = "untrusted";
+}
+
+/// Marker for light blocks that have passed structural/validation checks
+/// but have not yet been cross-verified against a trusted state.
+#[derive(Debug)]
+pub struct Valid;
+
+impl StoreTree for Valid {
+    const TREE_NAME: &'static str = "valid";
+}
+
+/// An error surfaced by a [`LightStore`] backend. The in-memory [`Store`]
+/// never fails, but the sled-backed [`SledStore`] can hit disk I/O errors or
+/// find a record it can no longer deserialize (e.g. after an on-disk format
+/// change), both of which are expected conditions for a persistence layer
+/// rather than bugs to panic on.
+#[derive(Debug)]
+pub enum StoreError {
+    Sled(sled::Error),
+    Serialization(bincode::Error),
+}
+
+impl From<sled::Error> for StoreError {
+    fn from(err: sled::Error) -> Self {
+        StoreError::Sled(err)
+    }
+}
+
+impl From<bincode::Error> for StoreError {
+    fn from(err: bincode::Error) -> Self {
+        StoreError::Serialization(err)
+    }
+}
+
+/// A backend capable of persisting light blocks keyed by height.
+///
+/// Implemented by both the in-memory [`Store`] and the sled-backed
+/// [`SledStore`], so that [`StoreReader`]/[`StoreReadWriter`] (and anything
+/// built on top of them, such as the demuxer's `State`) can be generic over
+/// the underlying storage.
+pub trait LightStore<T>: Debug + Send + Sync {
+    fn get(&self, height: Height) -> Result<Option<LightBlock>, StoreError>;
+    fn add(&mut self, light_block: LightBlock) -> Result<(), StoreError>;
+    fn all(&self) -> Result<Vec<LightBlock>, StoreError>;
+    fn latest_height(&self) -> Result<Option<Height>, StoreError>;
+
+    fn latest(&self) -> Result<Option<LightBlock>, StoreError> {
+        match self.latest_height()? {
+            Some(height) => self.get(height),
+            None => Ok(None),
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Store<T> {
     store: BTreeMap<Height, LightBlock>,
@@ -27,18 +92,6 @@ impl<T> Store<T> {
             marker: PhantomData,
         }
     }
-
-    pub fn split(self) -> (StoreReader<T>, StoreReadWriter<T>) {
-        let store = Arc::new(RwLock::new(self));
-
-        let reader = StoreReader {
-            store: store.clone(),
-        };
-
-        let writer = StoreReadWriter { store };
-
-        (reader, writer)
-    }
 }
 
 impl<T> Store<T> {
@@ -63,43 +116,204 @@ impl<T> Store<T> {
     }
 }
 
+impl<T: Debug + Send + Sync + 'static> LightStore<T> for Store<T> {
+    fn get(&self, height: Height) -> Result<Option<LightBlock>, StoreError> {
+        Ok(Store::get(self, height).cloned())
+    }
+
+    fn add(&mut self, light_block: LightBlock) -> Result<(), StoreError> {
+        Store::add(self, light_block);
+        Ok(())
+    }
+
+    fn all(&self) -> Result<Vec<LightBlock>, StoreError> {
+        Ok(Store::all(self).into_iter().cloned().collect())
+    }
+
+    fn latest_height(&self) -> Result<Option<Height>, StoreError> {
+        Ok(Store::latest_height(self))
+    }
+}
+
+impl<T: Debug + Send + Sync + 'static> Store<T> {
+    pub fn split(self) -> (StoreReader<T>, StoreReadWriter<T>) {
+        split_store(self)
+    }
+}
+
+/// A `sled`-backed light block store, so that light blocks survive restarts.
+///
+/// Heights are stored as big-endian bytes so sled's natural key ordering
+/// matches height ordering, which `latest_height` relies on. Light blocks
+/// are serialized with `bincode`.
+/// `Trusted`, `Valid` and `Untrusted` are each kept in their own tree (see
+/// [`StoreTree`]) so a single sled database file can back all three stores
+/// at once.
+#[derive(Debug)]
+pub struct SledStore<T> {
+    tree: sled::Tree,
+    marker: PhantomData<T>,
+}
+
+impl<T: StoreTree> SledStore<T> {
+    pub fn new(db: &sled::Db) -> sled::Result<Self> {
+        let tree = db.open_tree(T::TREE_NAME)?;
+
+        Ok(Self {
+            tree,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<T: StoreTree + Debug + Send + Sync + 'static> SledStore<T> {
+    pub fn split(self) -> (StoreReader<T>, StoreReadWriter<T>) {
+        split_store(self)
+    }
+}
+
+impl<T: StoreTree + Debug + Send + Sync + 'static> LightStore<T> for SledStore<T> {
+    fn get(&self, height: Height) -> Result<Option<LightBlock>, StoreError> {
+        match self.tree.get(height_key(height))? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn add(&mut self, light_block: LightBlock) -> Result<(), StoreError> {
+        let key = height_key(light_block.height);
+        let bytes = bincode::serialize(&light_block)?;
+
+        self.tree.insert(key, bytes)?;
+        Ok(())
+    }
+
+    fn all(&self) -> Result<Vec<LightBlock>, StoreError> {
+        self.tree
+            .iter()
+            .values()
+            .map(|bytes| Ok(bincode::deserialize(&bytes?)?))
+            .collect()
+    }
+
+    fn latest_height(&self) -> Result<Option<Height>, StoreError> {
+        match self.tree.last()? {
+            Some((key, _)) => Ok(Some(height_from_key(&key))),
+            None => Ok(None),
+        }
+    }
+}
+
+fn height_key(height: Height) -> [u8; 8] {
+    u64::from(height).to_be_bytes()
+}
+
+fn height_from_key(key: &[u8]) -> Height {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(key);
+    Height::from(u64::from_be_bytes(bytes))
+}
+
+fn split_store<T, S>(store: S) -> (StoreReader<T>, StoreReadWriter<T>)
+where
+    S: LightStore<T> + 'static,
+{
+    let store: Arc<RwLock<dyn LightStore<T>>> = Arc::new(RwLock::new(store));
+
+    let reader = StoreReader {
+        store: store.clone(),
+    };
+
+    let writer = StoreReadWriter { store };
+
+    (reader, writer)
+}
+
 #[derive(Clone, Debug)]
 pub struct StoreReader<T> {
-    store: Arc<RwLock<Store<T>>>,
+    store: Arc<RwLock<dyn LightStore<T>>>,
 }
 
 impl<T> StoreReader<T> {
-    pub fn get(&self, height: Height) -> Option<LightBlock> {
-        self.store.read().unwrap().get(height).cloned()
+    pub fn get(&self, height: Height) -> Result<Option<LightBlock>, StoreError> {
+        self.store.read().unwrap().get(height)
     }
 
-    pub fn latest_height(&self) -> Option<Height> {
+    pub fn latest_height(&self) -> Result<Option<Height>, StoreError> {
         self.store.read().unwrap().latest_height()
     }
 
-    pub fn latest(&self) -> Option<LightBlock> {
-        self.store.read().unwrap().latest().cloned()
+    pub fn latest(&self) -> Result<Option<LightBlock>, StoreError> {
+        self.store.read().unwrap().latest()
     }
 
-    pub fn all(&self) -> Vec<LightBlock> {
-        self.store
-            .read()
-            .unwrap()
-            .all()
-            .into_iter()
-            .cloned()
-            .collect()
+    pub fn all(&self) -> Result<Vec<LightBlock>, StoreError> {
+        self.store.read().unwrap().all()
     }
 }
 
 #[derive(Debug)]
 pub struct StoreReadWriter<T> {
-    store: Arc<RwLock<Store<T>>>,
+    store: Arc<RwLock<dyn LightStore<T>>>,
 }
 
 impl<T> StoreReadWriter<T> {
-    pub fn add(&mut self, light_block: LightBlock) {
+    pub fn add(&mut self, light_block: LightBlock) -> Result<(), StoreError> {
         let mut store = self.store.write().unwrap();
-        store.add(light_block);
+        store.add(light_block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light_block(height: u64) -> LightBlock {
+        LightBlock {
+            height: Height::from(height),
+            time: Time::now(),
+        }
+    }
+
+    #[test]
+    fn sled_store_round_trips_across_reopening_the_database() {
+        let path =
+            std::env::temp_dir().join(format!("light-spike-store-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+
+        {
+            let db = sled::open(&path).expect("failed to open sled db");
+            let mut store = SledStore::<Trusted>::new(&db).expect("failed to open trusted tree");
+
+            store.add(light_block(1)).expect("failed to add light block");
+            store.add(light_block(3)).expect("failed to add light block");
+            store.add(light_block(2)).expect("failed to add light block");
+        }
+
+        {
+            let db = sled::open(&path).expect("failed to reopen sled db");
+            let store = SledStore::<Trusted>::new(&db).expect("failed to reopen trusted tree");
+
+            assert_eq!(
+                store.latest_height().expect("latest_height failed"),
+                Some(Height::from(3))
+            );
+
+            let fetched = store.get(Height::from(2)).expect("get failed");
+            assert_eq!(fetched.map(|lb| lb.height), Some(Height::from(2)));
+
+            let heights: Vec<Height> = store
+                .all()
+                .expect("all failed")
+                .into_iter()
+                .map(|lb| lb.height)
+                .collect();
+            assert_eq!(
+                heights,
+                vec![Height::from(1), Height::from(2), Height::from(3)]
+            );
+        }
+
+        let _ = std::fs::remove_dir_all(&path);
     }
 }